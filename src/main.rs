@@ -1,78 +1,318 @@
-use anyhow::{Context, Result};
-use clap::Parser;
+use anyhow::{anyhow, Context, Result};
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use clap::{Parser, ValueEnum};
 use colored::*;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufReader;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use tokio::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskCx, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UdpSocket, UnixListener, UnixStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
 pub mod logger;
 
+/// How long a UDP session may stay idle before its upstream socket is reaped
+const UDP_SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Direction tags baked into every encrypted tunnel frame's nonce, so the
+/// two independent byte streams making up one connection never reuse each
+/// other's nonce space under the shared derived key.
+const DIR_CLIENT_TO_SERVER: u8 = 0;
+const DIR_SERVER_TO_CLIENT: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Inspect {
+    /// Don't print any payload, just the byte counts (default)
+    None,
+    /// Canonical hexdump: offset, hex bytes, printable-ASCII gutter
+    Hex,
+    /// Offset and printable-ASCII gutter, without the hex columns
+    Ascii,
+}
+
 #[derive(Parser)]
 #[command(name = env!("CARGO_PKG_NAME"))]
 #[command(about = env!("CARGO_PKG_DESCRIPTION"))]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(author = env!("CARGO_PKG_AUTHORS"))]
 struct Args {
-    /// Target address to forward traffic to (ip:port)
-    target: String,
+    /// Target to forward traffic to: ip:port or unix:/path/to.sock. Not used
+    /// with --serve-relay, which has no local target of its own.
+    target: Option<String>,
 
-    /// Listen address and port. Can be just a port (defaults to 0.0.0.0:port) or ip:port
+    /// Listen address and port, or unix:/path/to.sock. Can be just a port
+    /// (defaults to 0.0.0.0:port) or ip:port. In --relay mode this is not
+    /// used, since the process dials out instead of listening.
     #[arg(short = 'L', long = "listen")]
-    listen: String,
+    listen: Option<String>,
+
+    /// Protocol to forward
+    #[arg(short = 'p', long = "protocol", value_enum, default_value_t = Protocol::Tcp)]
+    protocol: Protocol,
+
+    /// Run in client-relay mode: dial out to a relay server at this address
+    /// instead of listening, so `fwdp` can traverse NAT without inbound
+    /// firewall rules. Pairs with a `--serve-relay` process on a public host.
+    #[arg(long = "relay")]
+    relay: Option<String>,
+
+    /// Run in relay-server mode: listen for public clients on `--listen` and
+    /// multiplex them over the connection from a `--relay` client on
+    /// `--relay-listen`.
+    #[arg(long = "serve-relay")]
+    serve_relay: bool,
+
+    /// Address relay clients dial in to when running `--serve-relay`
+    #[arg(long = "relay-listen")]
+    relay_listen: Option<String>,
+
+    /// Run in encrypted-tunnel server mode: accept the encrypted connection
+    /// from an `--encrypt-client` peer on `--listen` and forward decrypted
+    /// traffic to the plaintext `target`.
+    #[arg(long = "encrypt-server")]
+    encrypt_server: bool,
+
+    /// Run in encrypted-tunnel client mode: accept plaintext clients on
+    /// `--listen` and forward them, encrypted, to an `--encrypt-server`
+    /// peer at `target`.
+    #[arg(long = "encrypt-client")]
+    encrypt_client: bool,
+
+    /// Shared passphrase for `--encrypt-server`/`--encrypt-client`, run
+    /// through a BLAKE3 key derivation to produce the ChaCha20-Poly1305 key
+    /// so the CLI never takes raw key bytes. Also required by
+    /// `--serve-relay`/`--relay`, where a separate derivation produces the
+    /// token the relay client presents to the relay server.
+    #[arg(long = "psk")]
+    psk: Option<String>,
+
+    /// Print the payload of each forwarded chunk: a canonical hexdump, or
+    /// an ASCII-only rendering
+    #[arg(long = "inspect", value_enum, default_value_t = Inspect::None)]
+    inspect: Inspect,
+
+    /// Terminate TLS on the listen side: accept a TLS handshake from
+    /// clients using `--cert`/`--key`, then forward the decrypted
+    /// plaintext to `target`
+    #[arg(long = "tls-listen")]
+    tls_listen: bool,
+
+    /// Originate TLS to the target: wrap the outbound connection to
+    /// `target` in a TLS handshake instead of forwarding plaintext
+    #[arg(long = "tls-target")]
+    tls_target: bool,
+
+    /// PEM certificate chain for `--tls-listen`
+    #[arg(long = "cert")]
+    cert: Option<PathBuf>,
+
+    /// PEM private key for `--tls-listen`
+    #[arg(long = "key")]
+    key: Option<PathBuf>,
+
+    /// Server name to send via SNI and verify against for `--tls-target`.
+    /// Defaults to the target's IP address, which only works against
+    /// a certificate issued for that IP or with `--tls-insecure`
+    #[arg(long = "tls-sni")]
+    tls_sni: Option<String>,
+
+    /// Skip verifying the upstream's certificate for `--tls-target`
+    #[arg(long = "tls-insecure")]
+    tls_insecure: bool,
+
+    /// Cap the number of concurrent connections. Once the limit is
+    /// reached, accepting the next connection blocks until an existing
+    /// one closes, instead of spawning an unbounded number of tasks.
+    /// Only applies to plain TCP forwarding (protocol tcp, no --relay or
+    /// --encrypt-*)
+    #[arg(long = "max-connections")]
+    max_connections: Option<usize>,
+
+    /// Throttle each direction of forwarded traffic to at most this many
+    /// bytes per second. Only applies to plain TCP forwarding (protocol
+    /// tcp, no --relay or --encrypt-*)
+    #[arg(long = "rate-limit", value_parser = clap::value_parser!(u64).range(1..))]
+    rate_limit: Option<u64>,
+
+    /// How long to wait for in-flight connections to drain after a
+    /// SIGINT/SIGTERM before exiting anyway. Only applies to plain TCP
+    /// forwarding (protocol tcp, no --relay or --encrypt-*)
+    #[arg(long = "drain-timeout", default_value_t = 30)]
+    drain_timeout: u64,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    // Parse target address
-    let target_addr: SocketAddr = args
+    if args.serve_relay {
+        let public_listen = EndpointSpec::parse_listen(
+            args.listen
+                .as_deref()
+                .with_context(|| "--serve-relay requires --listen for public clients")?,
+        )?;
+        let relay_listen: SocketAddr = parse_listen_address(
+            args.relay_listen
+                .as_deref()
+                .with_context(|| "--serve-relay requires --relay-listen")?,
+        )
+        .with_context(|| "invalid --relay-listen address")?;
+        let psk = args
+            .psk
+            .as_deref()
+            .with_context(|| "--serve-relay requires --psk, so only an authenticated --relay client can claim the control connection")?;
+        let auth_token = derive_relay_auth_token(psk);
+
+        println!(
+            "{} public {} <-> relay {}",
+            "relay-server:".blue().bold(),
+            public_listen,
+            relay_listen
+        );
+
+        return run_relay_server(public_listen, relay_listen, args.inspect, auth_token).await;
+    }
+
+    if let Some(relay) = &args.relay {
+        let relay_addr: SocketAddr = relay
+            .parse()
+            .with_context(|| format!("invalid relay address: {}", relay))?;
+        let target_spec = EndpointSpec::parse_target(
+            args.target
+                .as_deref()
+                .with_context(|| "--relay requires a target to forward to")?,
+        )?;
+        let psk = args
+            .psk
+            .as_deref()
+            .with_context(|| "--relay requires --psk, matching the --serve-relay process")?;
+        let auth_token = derive_relay_auth_token(psk);
+
+        println!(
+            "{} {} -> {}",
+            "relay-client:".blue().bold(),
+            relay_addr,
+            target_spec
+        );
+
+        return run_relay_client(relay_addr, target_spec, args.inspect, auth_token).await;
+    }
+
+    if args.encrypt_server {
+        let listen_spec = EndpointSpec::parse_listen(
+            args.listen
+                .as_deref()
+                .with_context(|| "--encrypt-server requires --listen")?,
+        )?;
+        let target_spec = EndpointSpec::parse_target(
+            args.target
+                .as_deref()
+                .with_context(|| "--encrypt-server requires a target to forward to")?,
+        )?;
+        let psk = args
+            .psk
+            .as_deref()
+            .with_context(|| "--encrypt-server requires --psk")?;
+        let key = derive_psk_key(psk);
+
+        println!(
+            "{} {} -> {} (encrypted)",
+            "encrypt-server:".blue().bold(),
+            listen_spec,
+            target_spec
+        );
+
+        return run_encrypt_server(listen_spec, target_spec, key, args.inspect).await;
+    }
+
+    if args.encrypt_client {
+        let listen_spec = EndpointSpec::parse_listen(
+            args.listen
+                .as_deref()
+                .with_context(|| "--encrypt-client requires --listen")?,
+        )?;
+        let target_spec = EndpointSpec::parse_target(args.target.as_deref().with_context(
+            || "--encrypt-client requires a target (the --encrypt-server peer)",
+        )?)?;
+        let psk = args
+            .psk
+            .as_deref()
+            .with_context(|| "--encrypt-client requires --psk")?;
+        let key = derive_psk_key(psk);
+
+        println!(
+            "{} {} -> {} (encrypted)",
+            "encrypt-client:".blue().bold(),
+            listen_spec,
+            target_spec
+        );
+
+        return run_encrypt_client(listen_spec, target_spec, key, args.inspect).await;
+    }
+
+    let target = args
         .target
-        .parse()
-        .with_context(|| format!("invalid target address: {}", args.target))?;
+        .as_deref()
+        .with_context(|| "a target is required")?;
+    let listen = args
+        .listen
+        .as_deref()
+        .with_context(|| "--listen is required")?;
 
-    // Parse listen address
-    let listen_addr: SocketAddr = parse_listen_address(&args.listen)
-        .with_context(|| format!("invalid listen address: {}", args.listen))?;
+    // Parse target endpoint
+    let target_spec =
+        EndpointSpec::parse_target(target).with_context(|| format!("invalid target address: {}", target))?;
 
-    // Create TCP listener
-    let listener = TcpListener::bind(listen_addr)
-        .await
-        .with_context(|| format!("failed to bind to {}", listen_addr))?;
+    // Parse listen endpoint
+    let listen_spec =
+        EndpointSpec::parse_listen(listen).with_context(|| format!("invalid listen address: {}", listen))?;
 
     println!(
         "{} {} -> {}",
         "forward:".blue().bold(),
-        listen_addr,
-        target_addr
-    );
-
-    println!(
-        "{} continuously recv listening on {}",
-        "*".blue().bold(),
-        listen_addr
+        listen_spec,
+        target_spec
     );
 
-    let counter = AtomicU64::new(1);
-
-    // Accept connections and handle them
-    while let Ok((client_stream, client_addr)) = listener.accept().await {
-        let id = counter.fetch_add(1, Ordering::SeqCst);
-        record!([id], "{} new connection from {}", "+".green(), client_addr);
-
-        let target_addr = target_addr.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(id, client_stream, target_addr).await {
-                error!(
-                    [id],
-                    "error handling connection from {}: {}", client_addr, e
-                );
-            } else {
-                record!([id], "{} connection from {} closed", "-".red(), client_addr);
-            }
-        });
+    match args.protocol {
+        Protocol::Tcp => {
+            let tls = build_tls_config(&args, &target_spec)?;
+            run_tcp(
+                listen_spec,
+                target_spec,
+                args.inspect,
+                tls,
+                args.max_connections,
+                args.rate_limit,
+                Duration::from_secs(args.drain_timeout),
+            )
+            .await
+        }
+        Protocol::Udp => {
+            let listen_addr = listen_spec
+                .into_socket_addr()
+                .with_context(|| "UDP forwarding requires an ip:port listen address")?;
+            let target_addr = target_spec
+                .into_socket_addr()
+                .with_context(|| "UDP forwarding requires an ip:port target address")?;
+            run_udp(listen_addr, target_addr, args.inspect).await
+        }
     }
-
-    Ok(())
 }
 
 fn parse_listen_address(listen: &str) -> Result<SocketAddr> {
@@ -89,6 +329,156 @@ fn parse_listen_address(listen: &str) -> Result<SocketAddr> {
     }
 }
 
+/// Either side of a forward: a TCP address or a Unix domain socket path.
+#[derive(Clone)]
+enum EndpointSpec {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl EndpointSpec {
+    fn parse_target(raw: &str) -> Result<Self> {
+        if let Some(path) = raw.strip_prefix("unix:") {
+            return Ok(EndpointSpec::Unix(PathBuf::from(path)));
+        }
+        let addr: SocketAddr = raw.parse().with_context(|| "invalid ip:port")?;
+        Ok(EndpointSpec::Tcp(addr))
+    }
+
+    fn parse_listen(raw: &str) -> Result<Self> {
+        if let Some(path) = raw.strip_prefix("unix:") {
+            return Ok(EndpointSpec::Unix(PathBuf::from(path)));
+        }
+        Ok(EndpointSpec::Tcp(parse_listen_address(raw)?))
+    }
+
+    fn into_socket_addr(self) -> Result<SocketAddr> {
+        match self {
+            EndpointSpec::Tcp(addr) => Ok(addr),
+            EndpointSpec::Unix(path) => Err(anyhow!(
+                "unix socket unix:{} is not supported in udp mode",
+                path.display()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EndpointSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EndpointSpec::Tcp(addr) => write!(f, "{}", addr),
+            EndpointSpec::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A connected stream from either transport, so `handle_connection` can
+/// splice any combination of TCP and Unix endpoints together.
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskCx<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskCx<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskCx<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            Stream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskCx<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            Stream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Either side of an accept loop: a TCP listener or a Unix listener.
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    async fn bind(spec: &EndpointSpec) -> Result<Self> {
+        match spec {
+            EndpointSpec::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("failed to bind to {}", addr))?;
+                Ok(Listener::Tcp(listener))
+            }
+            EndpointSpec::Unix(path) => {
+                // remove a stale socket file left behind by a previous run
+                let _ = std::fs::remove_file(path);
+                let listener = UnixListener::bind(path)
+                    .with_context(|| format!("failed to bind to unix:{}", path.display()))?;
+                Ok(Listener::Unix(listener))
+            }
+        }
+    }
+
+    async fn accept(&self) -> std::io::Result<(Stream, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), addr.to_string()))
+            }
+            Listener::Unix(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                let addr_str = addr
+                    .as_pathname()
+                    .map(|path| format!("unix:{}", path.display()))
+                    .unwrap_or_else(|| "unknown".to_string());
+                Ok((Stream::Unix(stream), addr_str))
+            }
+        }
+    }
+}
+
+async fn connect_target(spec: &EndpointSpec) -> Result<Stream> {
+    match spec {
+        EndpointSpec::Tcp(addr) => {
+            let stream = TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("failed to connect to target {}", addr))?;
+            Ok(Stream::Tcp(stream))
+        }
+        EndpointSpec::Unix(path) => {
+            let stream = UnixStream::connect(path)
+                .await
+                .with_context(|| format!("failed to connect to target unix:{}", path.display()))?;
+            Ok(Stream::Unix(stream))
+        }
+    }
+}
+
 macro_rules! fmt_addr_forward {
     ($id:expr, ($client_addr:expr, >>>, $target_addr:expr)) => {{
         use colored::*;
@@ -133,6 +523,165 @@ macro_rules! fmt_addr_forward {
 }
 
 macro_rules! copy_and_record {
+    // datagram variant: recv from a UdpSocket and send_to a fixed peer, reporting
+    // (packet_count, bytes, chunk) per datagram instead of stream reads. Idle
+    // for longer than $idle and the session is considered finished.
+    //
+    // NOTE: the keyword-prefixed arms (`datagram`/`encrypt`/`decrypt`) must
+    // come before the bare `$reader:expr => ...` arm below: once the bare
+    // arm's leading `$reader:expr` starts consuming tokens like `decrypt
+    // &mut x`, the `&` can continue the expression parse (as `decrypt & ...`)
+    // and hits a hard syntax error on `mut` instead of falling through to
+    // the next arm.
+    (datagram $reader:expr => $writer:expr, $peer:expr, $idle:expr, $callback:expr) => {{
+        async move {
+            let mut buf = [0u8; 8192]; // 8KB buffer
+            let mut total_packets = 0u64;
+            let mut total_bytes = 0u64;
+            loop {
+                let bytes_read = match tokio::time::timeout($idle, $reader.recv(&mut buf)).await {
+                    Ok(result) => result?,
+                    Err(_) => break, // idle timeout, reap the session
+                };
+                if bytes_read == 0 {
+                    continue; // empty datagram, keep the session alive
+                }
+
+                $writer.send_to(&buf[..bytes_read], $peer).await?;
+                total_packets += 1;
+                total_bytes += bytes_read as u64;
+
+                // Call callback for each datagram
+                $callback(total_packets, bytes_read, &buf[..bytes_read]);
+            }
+
+            Ok::<u64, anyhow::Error>(total_bytes)
+        }
+    }};
+
+    // encrypt variant: read up to an 8KiB plaintext chunk from $reader, seal
+    // it under $cipher with a monotonic per-direction nonce, and write the
+    // framed ciphertext (u32 length + 12-byte nonce + ciphertext) to
+    // $writer. Reports (plaintext_len, wire_len, chunk) per frame.
+    (encrypt $reader:expr => $writer:expr, $cipher:expr, $dir:expr, $callback:expr) => {{
+        async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let mut buf = [0u8; 8192]; // 8KB buffer, also the plaintext frame cap
+            let mut counter = 0u64;
+            let mut total_bytes = 0u64;
+            loop {
+                let bytes_read = $reader.read(&mut buf).await?;
+                if bytes_read == 0 {
+                    break; // EOF
+                }
+
+                let nonce = build_nonce($dir, counter);
+                counter += 1;
+                let ciphertext = $cipher
+                    .encrypt(&nonce, &buf[..bytes_read])
+                    .map_err(|_| anyhow!("failed to encrypt tunnel frame"))?;
+
+                $writer
+                    .write_u32_le((nonce.len() + ciphertext.len()) as u32)
+                    .await?;
+                $writer.write_all(&nonce).await?;
+                $writer.write_all(&ciphertext).await?;
+                $writer.flush().await?;
+                total_bytes += bytes_read as u64;
+
+                $callback(bytes_read, nonce.len() + ciphertext.len(), &buf[..bytes_read]);
+            }
+
+            Ok::<u64, anyhow::Error>(total_bytes)
+        }
+    }};
+
+    // decrypt variant: the inverse of `encrypt` above. Reads a framed
+    // ciphertext from $reader, rejects it unless its nonce counter strictly
+    // follows the last one accepted (no replays, no reordering), opens it
+    // with $cipher, and writes the recovered plaintext to $writer.
+    (decrypt $reader:expr => $writer:expr, $cipher:expr, $dir:expr, $callback:expr) => {{
+        async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let mut expected_counter = 0u64;
+            let mut total_bytes = 0u64;
+            loop {
+                let wire_len = match $reader.read_u32_le().await {
+                    Ok(len) => len as usize,
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(e.into()),
+                };
+                if wire_len < 12 {
+                    return Err(anyhow!("encrypted tunnel frame is shorter than a nonce"));
+                }
+                // 12-byte nonce + up to an 8KiB plaintext chunk (see the
+                // `encrypt` arm above) + 16-byte AEAD tag. Reject anything
+                // larger before allocating, since wire_len is attacker
+                // controlled and read before authentication.
+                if wire_len > 12 + 8192 + 16 {
+                    return Err(anyhow!(
+                        "encrypted tunnel frame of {} bytes exceeds the maximum frame size",
+                        wire_len
+                    ));
+                }
+
+                let mut framed = vec![0u8; wire_len];
+                $reader.read_exact(&mut framed).await?;
+                let (nonce_bytes, ciphertext) = framed.split_at(12);
+
+                let counter = u64::from_be_bytes(nonce_bytes[4..12].try_into().unwrap());
+                if nonce_bytes[0] != $dir || counter != expected_counter {
+                    return Err(anyhow!(
+                        "rejecting out-of-order or replayed tunnel frame (expected counter {}, got {})",
+                        expected_counter,
+                        counter
+                    ));
+                }
+                expected_counter += 1;
+
+                let plaintext = $cipher
+                    .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+                    .map_err(|_| anyhow!("failed to authenticate tunnel frame"))?;
+
+                $writer.write_all(&plaintext).await?;
+                total_bytes += plaintext.len() as u64;
+
+                $callback(plaintext.len(), wire_len, plaintext.as_slice());
+            }
+
+            Ok::<u64, anyhow::Error>(total_bytes)
+        }
+    }};
+
+    // throttled variant: the same as the bare arm below, but additionally
+    // runs each chunk through $limiter (an `Option<RateLimiter>`), sleeping
+    // whenever that direction's token-bucket budget is exhausted.
+    (throttled $reader:expr => $writer:expr, $limiter:expr, $callback:expr) => {{
+        async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let mut buf = [0u8; 8192]; // 8KB buffer
+            let mut total_bytes = 0u64;
+            let mut limiter = $limiter;
+            loop {
+                let bytes_read = $reader.read(&mut buf).await?;
+                if bytes_read == 0 {
+                    break; // EOF
+                }
+
+                if let Some(limiter) = limiter.as_mut() {
+                    limiter.throttle(bytes_read).await;
+                }
+
+                $writer.write_all(&buf[..bytes_read]).await?;
+                total_bytes += bytes_read as u64;
+
+                $callback(bytes_read, &buf[..bytes_read]);
+            }
+
+            Ok::<u64, anyhow::Error>(total_bytes)
+        }
+    }};
+
     // r => w, a function to call, input bytes read
     ($reader:expr => $writer:expr, $callback:expr) => {{
         async move {
@@ -148,8 +697,8 @@ macro_rules! copy_and_record {
                 $writer.write_all(&buf[..bytes_read]).await?;
                 total_bytes += bytes_read as u64;
 
-                // Call callback for each packet
-                $callback(bytes_read);
+                // Call callback for each packet, with the chunk for inspection
+                $callback(bytes_read, &buf[..bytes_read]);
             }
 
             Ok::<u64, anyhow::Error>(total_bytes)
@@ -157,71 +706,1385 @@ macro_rules! copy_and_record {
     }};
 }
 
-async fn handle_connection(
-    id: u64,
-    mut client_stream: TcpStream,
-    target_addr: SocketAddr,
-) -> Result<()> {
-    // Connect to the target server
-    let mut target_stream = TcpStream::connect(target_addr)
-        .await
-        .with_context(|| format!("failed to connect to target {}", target_addr))?;
+/// Derive the ChaCha20-Poly1305 tunnel key from the shared `--psk`
+/// passphrase.
+fn derive_psk_key(psk: &str) -> [u8; 32] {
+    blake3::derive_key("fwdp encrypted tunnel v1", psk.as_bytes())
+}
 
-    let client_addr_str = extract_addr(&client_stream);
-    let target_addr_str = extract_addr(&target_stream);
+/// Derive the token a `--relay` client must present before `--serve-relay`
+/// will treat its connection as the control connection, from a context
+/// distinct from `derive_psk_key` so the same `--psk` can't be replayed
+/// between the two purposes.
+fn derive_relay_auth_token(psk: &str) -> [u8; 32] {
+    blake3::derive_key("fwdp relay control auth v1", psk.as_bytes())
+}
 
-    let (mut client_read, mut client_write) = client_stream.split();
-    let (mut target_read, mut target_write) = target_stream.split();
+/// Constant-time byte comparison, so a rejected relay auth token doesn't
+/// leak how many leading bytes matched via timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
 
-    let client_addr_clone = client_addr_str.clone();
-    let target_addr_clone = target_addr_str.clone();
-    let client_to_target = copy_and_record!(
-        &mut client_read =>
-        &mut target_write,
-        |bytes_read| {
-            record!(
-                [id],
-                "{} - {}",
-                fmt_addr_forward!(id, (client_addr_clone, >>>, target_addr_clone)),
-                format!("{} bytes", bytes_read).bright_black()
-            );
-        }
-    );
+/// Build the 12-byte nonce for an encrypted tunnel frame: a 1-byte
+/// direction tag, so the two halves of a bidirectional connection never
+/// reuse each other's nonce space under the same key, followed by a
+/// 3-byte pad and an 8-byte big-endian frame counter.
+fn build_nonce(dir: u8, counter: u64) -> Nonce {
+    let mut bytes = [0u8; 12];
+    bytes[0] = dir;
+    bytes[4..12].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&bytes)
+}
 
-    let target_to_client = copy_and_record!(
-        &mut target_read =>
-        &mut client_write,
-        |bytes_read| {
-            record!(
-                [id],
-                "{} - {}",
-                fmt_addr_forward!(id, (client_addr_str, <<<, target_addr_str)).dimmed(),
-                format!("{} bytes", bytes_read).bright_black()
-            );
-        }
-    );
+/// Render `buf` as a colorized payload dump, 16 bytes per line. In `Hex`
+/// mode this is a canonical hexdump: offset, hex bytes in two groups of
+/// eight, then the printable-ASCII gutter (non-printables as `.`). In
+/// `Ascii` mode only the offset and the gutter are shown.
+fn render_inspect(id: u64, mode: Inspect, buf: &[u8]) -> Option<String> {
+    if mode == Inspect::None {
+        return None;
+    }
 
-    tokio::select! {
-        result = client_to_target => {
-            match result {
-                Ok(_) => {},
-                Err(e) => error!([id], "error in client to target transfer: {}", e),
+    let mut lines = Vec::new();
+    for (row, chunk) in buf.chunks(16).enumerate() {
+        let mut line = format!("{:08x}  ", row * 16);
+
+        if mode == Inspect::Hex {
+            for col in 0..16 {
+                match chunk.get(col) {
+                    Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+                    None => line.push_str("   "),
+                }
+                if col == 7 {
+                    line.push(' ');
+                }
             }
+            line.push(' ');
         }
-        result = target_to_client => {
-            match result {
-                Ok(_) => {},
-                Err(e) => error!([id], "error in target to client transfer: {}", e),
-            }
+
+        line.push('|');
+        for byte in chunk {
+            let c = if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            };
+            line.push(c);
         }
+        line.push('|');
+
+        lines.push(line);
     }
 
-    Ok(())
+    Some(lines.join("\n").color(logger::color_map(id)).to_string())
+}
+
+/// Marker trait so a TLS-wrapped stream and a plain [`Stream`] can be
+/// handled as the same boxed type by `handle_connection`.
+trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+type BoxedStream = Box<dyn AsyncStream>;
+
+/// TLS setup resolved once at startup: an acceptor to terminate TLS from
+/// clients on the listen side, a connector plus the server name to verify
+/// against to originate TLS to the target, or both, or neither.
+#[derive(Clone, Default)]
+struct TlsConfig {
+    listen: Option<Arc<TlsAcceptor>>,
+    target: Option<(Arc<TlsConnector>, ServerName<'static>)>,
+}
+
+fn build_tls_config(args: &Args, target_spec: &EndpointSpec) -> Result<TlsConfig> {
+    let listen = if args.tls_listen {
+        let cert = args
+            .cert
+            .as_deref()
+            .with_context(|| "--tls-listen requires --cert")?;
+        let key = args
+            .key
+            .as_deref()
+            .with_context(|| "--tls-listen requires --key")?;
+        let config = load_tls_server_config(cert, key)?;
+        Some(Arc::new(TlsAcceptor::from(Arc::new(config))))
+    } else {
+        None
+    };
+
+    let target = if args.tls_target {
+        let server_name = match &args.tls_sni {
+            Some(sni) => ServerName::try_from(sni.clone())
+                .with_context(|| format!("invalid --tls-sni {}", sni))?,
+            None => {
+                let addr = target_spec.clone().into_socket_addr().with_context(|| {
+                    "--tls-target without --tls-sni requires an ip:port target"
+                })?;
+                ServerName::IpAddress(addr.ip().into())
+            }
+        };
+        let config = build_tls_client_config(args.tls_insecure);
+        Some((Arc::new(TlsConnector::from(Arc::new(config))), server_name))
+    } else {
+        None
+    };
+
+    Ok(TlsConfig { listen, target })
+}
+
+fn load_tls_server_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig> {
+    let cert_file = std::fs::File::open(cert_path)
+        .with_context(|| format!("failed to open --cert {}", cert_path.display()))?;
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("failed to parse certificate chain in {}", cert_path.display()))?;
+
+    let key_file = std::fs::File::open(key_path)
+        .with_context(|| format!("failed to open --key {}", key_path.display()))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+        .with_context(|| format!("failed to parse private key in {}", key_path.display()))?
+        .with_context(|| format!("no private key found in {}", key_path.display()))?;
+
+    ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| "invalid TLS certificate/key pair")
+}
+
+fn build_tls_client_config(insecure: bool) -> ClientConfig {
+    let builder = ClientConfig::builder();
+    if insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoCertVerification))
+            .with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        builder.with_root_certificates(roots).with_no_client_auth()
+    }
 }
 
-fn extract_addr(stream: &TcpStream) -> String {
-    stream
-        .peer_addr()
-        .map(|addr| addr.to_string())
-        .unwrap_or_else(|_| "unknown".to_string())
+/// Certificate verifier for `--tls-insecure`: accepts any certificate the
+/// upstream presents, so `fwdp` can originate TLS to a host with a
+/// self-signed or otherwise unverifiable certificate.
+#[derive(Debug)]
+struct NoCertVerification;
+
+impl ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::aws_lc_rs::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Pull the subject common name out of a peer certificate, for the
+/// handshake log line. Returns `None` if the certificate doesn't parse or
+/// carries no CN.
+fn extract_cert_cn(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let cn = parsed
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(|s| s.to_string());
+    cn
+}
+
+fn log_tls_handshake(
+    id: u64,
+    peer: &str,
+    version: Option<rustls::ProtocolVersion>,
+    alpn: Option<&[u8]>,
+    peer_cert: Option<&CertificateDer<'_>>,
+) {
+    let version = version
+        .map(|v| format!("{:?}", v))
+        .unwrap_or_else(|| "unknown".to_string());
+    let alpn = alpn
+        .map(|proto| String::from_utf8_lossy(proto).to_string())
+        .unwrap_or_else(|| "none".to_string());
+    let cn = peer_cert
+        .and_then(extract_cert_cn)
+        .unwrap_or_else(|| "none".to_string());
+    record!(
+        [id],
+        "{} TLS handshake with {}: {} alpn={} peer-cn={}",
+        "~".cyan(),
+        peer,
+        version,
+        alpn,
+        cn
+    );
+}
+
+/// Per-direction token-bucket throttle for `--rate-limit`: accrues `rate`
+/// bytes/sec of budget and sleeps whenever a read would overdraw it, so a
+/// single connection can't exceed its configured share of bandwidth.
+struct RateLimiter {
+    rate: u64,
+    tokens: f64,
+    last_refill: tokio::time::Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u64) -> Self {
+        RateLimiter {
+            rate,
+            tokens: rate as f64,
+            last_refill: tokio::time::Instant::now(),
+        }
+    }
+
+    async fn throttle(&mut self, bytes: usize) {
+        let now = tokio::time::Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+
+        self.tokens -= bytes as f64;
+        if self.tokens < 0.0 {
+            let wait_secs = -self.tokens / self.rate as f64;
+            tokio::time::sleep(Duration::from_secs_f64(wait_secs)).await;
+            self.tokens = 0.0;
+            // Re-timestamp now that the sleep has actually paid down the
+            // deficit: if last_refill stayed at its pre-sleep value, the
+            // next call's elapsed would double-count this sleep and hand
+            // out free tokens, roughly doubling the effective rate.
+            self.last_refill = tokio::time::Instant::now();
+        }
+    }
+}
+
+/// Resolves once SIGINT or SIGTERM is received, so the accept loop can stop
+/// taking new connections and begin a graceful drain.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+async fn run_tcp(
+    listen_spec: EndpointSpec,
+    target_spec: EndpointSpec,
+    inspect: Inspect,
+    tls: TlsConfig,
+    max_connections: Option<usize>,
+    rate_limit: Option<u64>,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let listener = Listener::bind(&listen_spec).await?;
+
+    println!(
+        "{} continuously recv listening on {}",
+        "*".blue().bold(),
+        listen_spec
+    );
+
+    let counter = AtomicU64::new(1);
+    let max_permits = max_connections.unwrap_or(tokio::sync::Semaphore::MAX_PERMITS);
+    let connection_limit = Arc::new(tokio::sync::Semaphore::new(max_permits));
+    let mut shutdown = Box::pin(wait_for_shutdown_signal());
+
+    // Accept connections and handle them, racing each accept against the
+    // shutdown signal so a SIGINT/SIGTERM stops new work immediately.
+    loop {
+        let (client_stream, client_addr) = tokio::select! {
+            biased;
+            _ = &mut shutdown => {
+                record!(
+                    "{} shutdown signal received, no longer accepting connections",
+                    "*".yellow()
+                );
+                break;
+            }
+            accepted = listener.accept() => match accepted {
+                Ok(pair) => pair,
+                Err(_) => break,
+            },
+        };
+
+        let id = counter.fetch_add(1, Ordering::SeqCst);
+        record!([id], "{} new connection from {}", "+".green(), client_addr);
+
+        // Also race the permit wait against shutdown: otherwise a signal
+        // arriving while every permit is held by a stuck connection would
+        // never be noticed, and the drain wait below would never start.
+        let permit = tokio::select! {
+            biased;
+            _ = &mut shutdown => {
+                record!(
+                    [id],
+                    "{} shutdown signal received while waiting for a free connection slot, dropping connection from {}",
+                    "*".yellow(),
+                    client_addr
+                );
+                break;
+            }
+            permit = connection_limit.clone().acquire_owned() => {
+                permit.expect("connection-limit semaphore is never closed")
+            }
+        };
+
+        let target_spec = target_spec.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) =
+                handle_connection(id, client_stream, target_spec, inspect, tls, rate_limit).await
+            {
+                error!(
+                    [id],
+                    "error handling connection from {}: {}", client_addr, e
+                );
+            } else {
+                record!([id], "{} connection from {} closed", "-".red(), client_addr);
+            }
+        });
+    }
+
+    let in_flight = max_permits - connection_limit.available_permits();
+    if in_flight > 0 {
+        record!(
+            "{} waiting up to {:?} for {} connection(s) to drain",
+            "*".yellow(),
+            drain_timeout,
+            in_flight
+        );
+        let drained = tokio::time::timeout(drain_timeout, async {
+            while connection_limit.available_permits() < max_permits {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+        match drained {
+            Ok(_) => record!("{} all connections drained", "-".red()),
+            Err(_) => record!(
+                "{} drain timeout elapsed with connections still in flight, exiting",
+                "!".yellow()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_connection(
+    id: u64,
+    client_stream: Stream,
+    target_spec: EndpointSpec,
+    inspect: Inspect,
+    tls: TlsConfig,
+    rate_limit: Option<u64>,
+) -> Result<()> {
+    let client_addr_str = extract_addr(&client_stream);
+
+    let client_stream: BoxedStream = match &tls.listen {
+        Some(acceptor) => {
+            let tls_stream = acceptor
+                .accept(client_stream)
+                .await
+                .with_context(|| format!("TLS handshake with {} failed", client_addr_str))?;
+            let conn = tls_stream.get_ref().1;
+            log_tls_handshake(
+                id,
+                &client_addr_str,
+                conn.protocol_version(),
+                conn.alpn_protocol(),
+                conn.peer_certificates().and_then(|certs| certs.first()),
+            );
+            Box::new(tls_stream)
+        }
+        None => Box::new(client_stream),
+    };
+
+    // Connect to the target server
+    let target_stream = connect_target(&target_spec).await?;
+    let target_addr_str = extract_addr(&target_stream);
+
+    let target_stream: BoxedStream = match &tls.target {
+        Some((connector, server_name)) => {
+            let tls_stream = connector
+                .connect(server_name.clone(), target_stream)
+                .await
+                .with_context(|| format!("TLS handshake with {} failed", target_addr_str))?;
+            let conn = tls_stream.get_ref().1;
+            log_tls_handshake(
+                id,
+                &target_addr_str,
+                conn.protocol_version(),
+                conn.alpn_protocol(),
+                conn.peer_certificates().and_then(|certs| certs.first()),
+            );
+            Box::new(tls_stream)
+        }
+        None => Box::new(target_stream),
+    };
+
+    let (mut client_read, mut client_write) = tokio::io::split(client_stream);
+    let (mut target_read, mut target_write) = tokio::io::split(target_stream);
+
+    let client_addr_clone = client_addr_str.clone();
+    let target_addr_clone = target_addr_str.clone();
+    let client_to_target = copy_and_record!(
+        throttled &mut client_read =>
+        &mut target_write,
+        rate_limit.map(RateLimiter::new),
+        |bytes_read, chunk| {
+            let arrow = fmt_addr_forward!(id, (client_addr_clone, >>>, target_addr_clone));
+            record!([id], "{} - {}", arrow, format!("{} bytes", bytes_read).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    let target_to_client = copy_and_record!(
+        throttled &mut target_read =>
+        &mut client_write,
+        rate_limit.map(RateLimiter::new),
+        |bytes_read, chunk| {
+            let arrow = fmt_addr_forward!(id, (client_addr_str, <<<, target_addr_str)).dimmed();
+            record!([id], "{} - {}", arrow, format!("{} bytes", bytes_read).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    tokio::select! {
+        result = client_to_target => {
+            match result {
+                Ok(_) => {},
+                Err(e) => error!([id], "error in client to target transfer: {}", e),
+            }
+        }
+        result = target_to_client => {
+            match result {
+                Ok(_) => {},
+                Err(e) => error!([id], "error in target to client transfer: {}", e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_addr(stream: &Stream) -> String {
+    match stream {
+        Stream::Tcp(s) => s
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|_| "unknown".to_string()),
+        Stream::Unix(s) => s
+            .peer_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|path| format!("unix:{}", path.display())))
+            .unwrap_or_else(|| "unknown".to_string()),
+    }
+}
+
+/// Per-peer UDP session: an upstream socket connected to the target, keyed
+/// by the client's address. The session is reaped once no datagram has come
+/// back from the target for `UDP_SESSION_IDLE_TIMEOUT`; datagrams from the
+/// client alone do not keep it alive.
+struct UdpSession {
+    id: u64,
+    upstream: Arc<UdpSocket>,
+    packets_forwarded: Arc<AtomicU64>,
+}
+
+type UdpSessions = Arc<Mutex<HashMap<SocketAddr, UdpSession>>>;
+
+async fn run_udp(listen_addr: SocketAddr, target_addr: SocketAddr, inspect: Inspect) -> Result<()> {
+    let socket = Arc::new(
+        UdpSocket::bind(listen_addr)
+            .await
+            .with_context(|| format!("failed to bind to {}", listen_addr))?,
+    );
+
+    println!(
+        "{} continuously recv listening on {}",
+        "*".blue().bold(),
+        listen_addr
+    );
+
+    let counter = AtomicU64::new(1);
+    let sessions: UdpSessions = Arc::new(Mutex::new(HashMap::new()));
+
+    let mut buf = [0u8; 8192];
+    loop {
+        let (bytes_read, client_addr) = socket.recv_from(&mut buf).await?;
+
+        let found = {
+            let sessions_guard = sessions.lock().unwrap();
+            sessions_guard
+                .get(&client_addr)
+                .map(|s| (s.id, s.upstream.clone(), s.packets_forwarded.clone()))
+        };
+
+        let (id, upstream, packets_forwarded) = match found {
+            Some(found) => found,
+            None => {
+                let id = counter.fetch_add(1, Ordering::SeqCst);
+                record!([id], "{} new session from {}", "+".green(), client_addr);
+
+                let upstream = Arc::new(
+                    UdpSocket::bind(("0.0.0.0", 0))
+                        .await
+                        .with_context(|| "failed to bind upstream udp socket")?,
+                );
+                upstream
+                    .connect(target_addr)
+                    .await
+                    .with_context(|| format!("failed to connect to target {}", target_addr))?;
+
+                let packets_forwarded = Arc::new(AtomicU64::new(0));
+
+                sessions.lock().unwrap().insert(
+                    client_addr,
+                    UdpSession {
+                        id,
+                        upstream: upstream.clone(),
+                        packets_forwarded: packets_forwarded.clone(),
+                    },
+                );
+
+                spawn_udp_session_reader(
+                    id,
+                    socket.clone(),
+                    upstream.clone(),
+                    client_addr,
+                    target_addr,
+                    sessions.clone(),
+                    inspect,
+                );
+
+                (id, upstream, packets_forwarded)
+            }
+        };
+
+        match upstream.send(&buf[..bytes_read]).await {
+            Ok(_) => {
+                let packets = packets_forwarded.fetch_add(1, Ordering::SeqCst) + 1;
+                let arrow = fmt_addr_forward!(id, (client_addr.to_string(), >>>, target_addr.to_string()));
+                record!(
+                    [id],
+                    "{} - {}",
+                    arrow,
+                    format!("packet #{} ({} bytes)", packets, bytes_read).bright_black()
+                );
+                if let Some(dump) = render_inspect(id, inspect, &buf[..bytes_read]) {
+                    record!([id], "{}\n{}", arrow, dump);
+                }
+            }
+            Err(e) => {
+                error!(
+                    [id],
+                    "error forwarding datagram from {} to {}: {}", client_addr, target_addr, e
+                );
+            }
+        }
+    }
+}
+
+fn spawn_udp_session_reader(
+    id: u64,
+    socket: Arc<UdpSocket>,
+    upstream: Arc<UdpSocket>,
+    client_addr: SocketAddr,
+    target_addr: SocketAddr,
+    sessions: UdpSessions,
+    inspect: Inspect,
+) {
+    tokio::spawn(async move {
+        let client_addr_str = client_addr.to_string();
+        let target_addr_str = target_addr.to_string();
+
+        let result = copy_and_record!(
+            datagram upstream => socket,
+            client_addr,
+            UDP_SESSION_IDLE_TIMEOUT,
+            |packets, bytes_read, chunk| {
+                let arrow = fmt_addr_forward!(id, (client_addr_str, <<<, target_addr_str)).dimmed();
+                record!(
+                    [id],
+                    "{} - {}",
+                    arrow,
+                    format!("packet #{} ({} bytes)", packets, bytes_read).bright_black()
+                );
+                if let Some(dump) = render_inspect(id, inspect, chunk) {
+                    record!([id], "{}\n{}", arrow, dump);
+                }
+            }
+        )
+        .await;
+
+        sessions.lock().unwrap().remove(&client_addr);
+
+        match result {
+            Ok(_) => {
+                record!([id], "{} session from {} closed", "-".red(), client_addr);
+            }
+            Err(e) => {
+                error!([id], "error handling session from {}: {}", client_addr, e);
+            }
+        }
+    });
+}
+
+/// A frame multiplexed over the persistent relay control connection. Each
+/// public client accepted by the relay server becomes a logical stream,
+/// identified by `stream_id`, that rides alongside every other session on
+/// the single TCP connection to the relay client.
+enum Frame {
+    /// Relay server -> relay client: a new public client connected, open a
+    /// logical stream for it.
+    Open(u32),
+    /// Either direction: a chunk of payload for a logical stream.
+    Data(u32, Vec<u8>),
+    /// Either direction: the logical stream's local half closed.
+    Close(u32),
+}
+
+impl Frame {
+    async fn write<W: AsyncWrite + Unpin>(&self, w: &mut W) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        match self {
+            Frame::Open(id) => {
+                w.write_u8(0).await?;
+                w.write_u32_le(*id).await?;
+            }
+            Frame::Data(id, payload) => {
+                w.write_u8(1).await?;
+                w.write_u32_le(*id).await?;
+                w.write_u32_le(payload.len() as u32).await?;
+                w.write_all(payload).await?;
+            }
+            Frame::Close(id) => {
+                w.write_u8(2).await?;
+                w.write_u32_le(*id).await?;
+            }
+        }
+        w.flush().await
+    }
+
+    /// Returns `Ok(None)` on a clean EOF between frames.
+    async fn read<R: AsyncRead + Unpin>(r: &mut R) -> std::io::Result<Option<Frame>> {
+        use tokio::io::AsyncReadExt;
+        let tag = match r.read_u8().await {
+            Ok(tag) => tag,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let stream_id = r.read_u32_le().await?;
+        match tag {
+            0 => Ok(Some(Frame::Open(stream_id))),
+            2 => Ok(Some(Frame::Close(stream_id))),
+            1 => {
+                let len = r.read_u32_le().await? as usize;
+                // len is attacker controlled and read before any
+                // authentication, so cap it to the 8KiB pump buffer used
+                // everywhere else before allocating.
+                if len > 8192 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("relay data frame of {} bytes exceeds the maximum frame size", len),
+                    ));
+                }
+                let mut payload = vec![0u8; len];
+                r.read_exact(&mut payload).await?;
+                Ok(Some(Frame::Data(stream_id, payload)))
+            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unknown relay frame tag",
+            )),
+        }
+    }
+}
+
+/// One end of a logical stream demuxed from the relay control connection.
+/// Implements `AsyncRead`/`AsyncWrite` so it can be spliced with a real
+/// socket through the existing `copy_and_record!` pump, the same way a
+/// `Stream` is.
+struct LogicalStream {
+    stream_id: u32,
+    tx: tokio::sync::mpsc::UnboundedSender<Frame>,
+    rx: tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl AsyncRead for LogicalStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskCx<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.pending.is_empty() {
+            match this.rx.poll_recv(cx) {
+                Poll::Ready(Some(data)) => this.pending = data,
+                Poll::Ready(None) => return Poll::Ready(Ok(())), // peer closed
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = std::cmp::min(buf.remaining(), this.pending.len());
+        buf.put_slice(&this.pending[..n]);
+        this.pending.drain(..n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for LogicalStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskCx<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        match this.tx.send(Frame::Data(this.stream_id, buf.to_vec())) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "relay control channel closed",
+            ))),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskCx<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut TaskCx<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let _ = this.tx.send(Frame::Close(this.stream_id));
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Shared state for a relay server: the write queue to the single active
+/// relay client, and the demux table routing incoming `Data`/`Close` frames
+/// to the logical stream they belong to.
+#[derive(Default)]
+struct RelayHub {
+    control_tx: Mutex<Option<tokio::sync::mpsc::UnboundedSender<Frame>>>,
+    streams: Mutex<HashMap<u32, tokio::sync::mpsc::UnboundedSender<Vec<u8>>>>,
+}
+
+impl RelayHub {
+    fn route(&self, frame: Frame) {
+        match frame {
+            Frame::Data(stream_id, payload) => {
+                let tx = self.streams.lock().unwrap().get(&stream_id).cloned();
+                if let Some(tx) = tx {
+                    let _ = tx.send(payload);
+                }
+            }
+            Frame::Close(stream_id) => {
+                self.streams.lock().unwrap().remove(&stream_id);
+            }
+            Frame::Open(_) => {}
+        }
+    }
+}
+
+async fn run_control_writer(
+    mut write_half: tokio::net::tcp::OwnedWriteHalf,
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<Frame>,
+) {
+    while let Some(frame) = rx.recv().await {
+        if frame.write(&mut write_half).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn run_relay_server(
+    public_listen: EndpointSpec,
+    relay_listen: SocketAddr,
+    inspect: Inspect,
+    auth_token: [u8; 32],
+) -> Result<()> {
+    let hub = Arc::new(RelayHub::default());
+
+    let relay_listener = TcpListener::bind(relay_listen)
+        .await
+        .with_context(|| format!("failed to bind relay listener to {}", relay_listen))?;
+    let public_listener = Listener::bind(&public_listen).await?;
+
+    println!(
+        "{} continuously recv listening on {} (public) and {} (relay)",
+        "*".blue().bold(),
+        public_listen,
+        relay_listen
+    );
+
+    let control_hub = hub.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, addr)) = relay_listener.accept().await {
+            // Whoever authenticates here takes over every public client's
+            // logical stream, so require the --psk-derived token up front
+            // before treating the connection as the control connection.
+            use tokio::io::AsyncReadExt;
+            let mut presented = [0u8; 32];
+            match tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut presented))
+                .await
+            {
+                Ok(Ok(_)) if constant_time_eq(&presented, &auth_token) => {}
+                Ok(Ok(_)) => {
+                    warn!("rejecting relay client {} with a bad auth token", addr);
+                    continue;
+                }
+                Ok(Err(e)) => {
+                    warn!(
+                        "relay client {} disconnected before authenticating: {}",
+                        addr, e
+                    );
+                    continue;
+                }
+                Err(_) => {
+                    warn!("relay client {} timed out authenticating", addr);
+                    continue;
+                }
+            }
+
+            record!("{} relay client connected from {}", "+".green(), addr);
+
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            *control_hub.control_tx.lock().unwrap() = Some(tx);
+
+            let (mut read_half, write_half) = stream.into_split();
+            tokio::spawn(run_control_writer(write_half, rx));
+
+            let control_hub = control_hub.clone();
+            tokio::spawn(async move {
+                loop {
+                    match Frame::read(&mut read_half).await {
+                        Ok(Some(frame)) => control_hub.route(frame),
+                        Ok(None) => break,
+                        Err(e) => {
+                            error!("relay control connection error: {}", e);
+                            break;
+                        }
+                    }
+                }
+                *control_hub.control_tx.lock().unwrap() = None;
+                record!("{} relay client disconnected", "-".red());
+            });
+        }
+    });
+
+    let counter = AtomicU64::new(1);
+    let next_stream_id = AtomicU32::new(1);
+
+    while let Ok((client_stream, client_addr)) = public_listener.accept().await {
+        let id = counter.fetch_add(1, Ordering::SeqCst);
+        record!([id], "{} new connection from {}", "+".green(), client_addr);
+
+        let control_tx = hub.control_tx.lock().unwrap().clone();
+        let Some(control_tx) = control_tx else {
+            error!(
+                [id],
+                "no relay client connected, dropping connection from {}", client_addr
+            );
+            continue;
+        };
+
+        let stream_id = next_stream_id.fetch_add(1, Ordering::SeqCst);
+        let (data_tx, data_rx) = tokio::sync::mpsc::unbounded_channel();
+        hub.streams.lock().unwrap().insert(stream_id, data_tx);
+
+        if control_tx.send(Frame::Open(stream_id)).is_err() {
+            error!([id], "relay control channel closed");
+            hub.streams.lock().unwrap().remove(&stream_id);
+            continue;
+        }
+
+        let logical = LogicalStream {
+            stream_id,
+            tx: control_tx,
+            rx: data_rx,
+            pending: Vec::new(),
+        };
+
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_relay_leg(id, client_stream, logical, inspect).await {
+                error!(
+                    [id],
+                    "error handling connection from {}: {}", client_addr, e
+                );
+            } else {
+                record!([id], "{} connection from {} closed", "-".red(), client_addr);
+            }
+            hub.streams.lock().unwrap().remove(&stream_id);
+        });
+    }
+
+    Ok(())
+}
+
+async fn run_relay_client(
+    relay_addr: SocketAddr,
+    target_spec: EndpointSpec,
+    inspect: Inspect,
+    auth_token: [u8; 32],
+) -> Result<()> {
+    println!(
+        "{} continuously dialing relay server {}",
+        "*".blue().bold(),
+        relay_addr
+    );
+
+    let counter = AtomicU64::new(1);
+
+    loop {
+        let mut stream = match TcpStream::connect(relay_addr).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("failed to connect to relay server {}: {}", relay_addr, e);
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                continue;
+            }
+        };
+        // Present the --psk-derived auth token before anything else, so the
+        // relay server knows this connection is allowed to become the
+        // control connection.
+        use tokio::io::AsyncWriteExt;
+        if let Err(e) = stream.write_all(&auth_token).await {
+            error!(
+                "failed to authenticate to relay server {}: {}",
+                relay_addr, e
+            );
+            tokio::time::sleep(Duration::from_secs(3)).await;
+            continue;
+        }
+        record!("{} connected to relay server {}", "+".green(), relay_addr);
+
+        let hub = Arc::new(RelayHub::default());
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        *hub.control_tx.lock().unwrap() = Some(tx);
+
+        let (mut read_half, write_half) = stream.into_split();
+        let writer = tokio::spawn(run_control_writer(write_half, rx));
+
+        loop {
+            match Frame::read(&mut read_half).await {
+                Ok(Some(Frame::Open(stream_id))) => {
+                    let id = counter.fetch_add(1, Ordering::SeqCst);
+                    record!([id], "{} new logical stream #{}", "+".green(), stream_id);
+
+                    let (data_tx, data_rx) = tokio::sync::mpsc::unbounded_channel();
+                    hub.streams.lock().unwrap().insert(stream_id, data_tx);
+
+                    let logical = LogicalStream {
+                        stream_id,
+                        tx: hub.control_tx.lock().unwrap().clone().unwrap(),
+                        rx: data_rx,
+                        pending: Vec::new(),
+                    };
+                    let target_spec = target_spec.clone();
+                    let hub = hub.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            handle_relay_target_leg(id, target_spec, logical, inspect).await
+                        {
+                            error!([id], "error handling logical stream #{}: {}", stream_id, e);
+                        } else {
+                            record!([id], "{} logical stream #{} closed", "-".red(), stream_id);
+                        }
+                        hub.streams.lock().unwrap().remove(&stream_id);
+                    });
+                }
+                Ok(Some(frame)) => hub.route(frame),
+                Ok(None) => break,
+                Err(e) => {
+                    error!("relay control connection error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        writer.abort();
+        record!("{} disconnected from relay server, reconnecting", "-".red());
+        tokio::time::sleep(Duration::from_secs(3)).await;
+    }
+}
+
+/// Splice a public client socket with its demuxed logical stream (relay
+/// server side).
+async fn handle_relay_leg(
+    id: u64,
+    client_stream: Stream,
+    logical: LogicalStream,
+    inspect: Inspect,
+) -> Result<()> {
+    let client_addr_str = extract_addr(&client_stream);
+
+    let (mut client_read, mut client_write) = tokio::io::split(client_stream);
+    let (mut logical_read, mut logical_write) = tokio::io::split(logical);
+
+    let client_addr_clone = client_addr_str.clone();
+    let relay_label = "relay".to_string();
+    let relay_label_clone = relay_label.clone();
+    let client_to_relay = copy_and_record!(
+        &mut client_read =>
+        &mut logical_write,
+        |bytes_read, chunk| {
+            let arrow = fmt_addr_forward!(id, (client_addr_clone, >>>, relay_label_clone));
+            record!([id], "{} - {}", arrow, format!("{} bytes", bytes_read).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    let relay_to_client = copy_and_record!(
+        &mut logical_read =>
+        &mut client_write,
+        |bytes_read, chunk| {
+            let arrow = fmt_addr_forward!(id, (client_addr_str, <<<, relay_label)).dimmed();
+            record!([id], "{} - {}", arrow, format!("{} bytes", bytes_read).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    tokio::select! {
+        result = client_to_relay => {
+            if let Err(e) = result {
+                error!([id], "error in client to relay transfer: {}", e);
+            }
+        }
+        result = relay_to_client => {
+            if let Err(e) = result {
+                error!([id], "error in relay to client transfer: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Splice a freshly dialed target socket with its demuxed logical stream
+/// (relay client side).
+async fn handle_relay_target_leg(
+    id: u64,
+    target_spec: EndpointSpec,
+    logical: LogicalStream,
+    inspect: Inspect,
+) -> Result<()> {
+    let target_stream = connect_target(&target_spec).await?;
+    let target_addr_str = extract_addr(&target_stream);
+
+    let (mut target_read, mut target_write) = tokio::io::split(target_stream);
+    let (mut logical_read, mut logical_write) = tokio::io::split(logical);
+
+    let target_addr_clone = target_addr_str.clone();
+    let relay_label = "relay".to_string();
+    let relay_label_clone = relay_label.clone();
+    let target_to_relay = copy_and_record!(
+        &mut target_read =>
+        &mut logical_write,
+        |bytes_read, chunk| {
+            let arrow = fmt_addr_forward!(id, (target_addr_clone, >>>, relay_label_clone));
+            record!([id], "{} - {}", arrow, format!("{} bytes", bytes_read).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    let relay_to_target = copy_and_record!(
+        &mut logical_read =>
+        &mut target_write,
+        |bytes_read, chunk| {
+            let arrow = fmt_addr_forward!(id, (target_addr_str, <<<, relay_label)).dimmed();
+            record!([id], "{} - {}", arrow, format!("{} bytes", bytes_read).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    tokio::select! {
+        result = target_to_relay => {
+            if let Err(e) = result {
+                error!([id], "error in target to relay transfer: {}", e);
+            }
+        }
+        result = relay_to_target => {
+            if let Err(e) = result {
+                error!([id], "error in relay to target transfer: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `--encrypt-server` side of an encrypted tunnel: accept the encrypted
+/// connection from an `--encrypt-client` peer and splice the decrypted
+/// traffic with a freshly dialed plaintext target.
+async fn run_encrypt_server(
+    listen_spec: EndpointSpec,
+    target_spec: EndpointSpec,
+    key: [u8; 32],
+    inspect: Inspect,
+) -> Result<()> {
+    let listener = Listener::bind(&listen_spec).await?;
+
+    println!(
+        "{} continuously recv listening on {}",
+        "*".blue().bold(),
+        listen_spec
+    );
+
+    let counter = AtomicU64::new(1);
+
+    while let Ok((peer_stream, peer_addr)) = listener.accept().await {
+        let id = counter.fetch_add(1, Ordering::SeqCst);
+        record!(
+            [id],
+            "{} new encrypted connection from {}",
+            "+".green(),
+            peer_addr
+        );
+
+        let target_spec = target_spec.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                handle_encrypt_server_leg(id, peer_stream, target_spec, key, inspect).await
+            {
+                error!(
+                    [id],
+                    "error handling encrypted connection from {}: {}", peer_addr, e
+                );
+            } else {
+                record!(
+                    [id],
+                    "{} encrypted connection from {} closed",
+                    "-".red(),
+                    peer_addr
+                );
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_encrypt_server_leg(
+    id: u64,
+    peer_stream: Stream,
+    target_spec: EndpointSpec,
+    key: [u8; 32],
+    inspect: Inspect,
+) -> Result<()> {
+    let target_stream = connect_target(&target_spec).await?;
+
+    let peer_addr_str = extract_addr(&peer_stream);
+    let target_addr_str = extract_addr(&target_stream);
+
+    let (mut peer_read, mut peer_write) = tokio::io::split(peer_stream);
+    let (mut target_read, mut target_write) = tokio::io::split(target_stream);
+
+    let decrypt_cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let encrypt_cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+
+    let peer_addr_clone = peer_addr_str.clone();
+    let target_addr_clone = target_addr_str.clone();
+    let decrypt_to_target = copy_and_record!(
+        decrypt &mut peer_read =>
+        &mut target_write,
+        decrypt_cipher,
+        DIR_CLIENT_TO_SERVER,
+        |plain_len, wire_len, chunk| {
+            let arrow = fmt_addr_forward!(id, (peer_addr_clone, >>>, target_addr_clone));
+            record!([id], "{} - {}", arrow, format!("{} bytes ({} on the wire)", plain_len, wire_len).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    let encrypt_to_peer = copy_and_record!(
+        encrypt &mut target_read =>
+        &mut peer_write,
+        encrypt_cipher,
+        DIR_SERVER_TO_CLIENT,
+        |plain_len, wire_len, chunk| {
+            let arrow = fmt_addr_forward!(id, (peer_addr_str, <<<, target_addr_str)).dimmed();
+            record!([id], "{} - {}", arrow, format!("{} bytes ({} on the wire)", plain_len, wire_len).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    tokio::select! {
+        result = decrypt_to_target => {
+            if let Err(e) = result {
+                error!([id], "error decrypting peer to target transfer: {}", e);
+            }
+        }
+        result = encrypt_to_peer => {
+            if let Err(e) = result {
+                error!([id], "error encrypting target to peer transfer: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `--encrypt-client` side of an encrypted tunnel: accept plaintext local
+/// clients and splice each one with a freshly dialed encrypted connection
+/// to the `--encrypt-server` peer.
+async fn run_encrypt_client(
+    listen_spec: EndpointSpec,
+    target_spec: EndpointSpec,
+    key: [u8; 32],
+    inspect: Inspect,
+) -> Result<()> {
+    let listener = Listener::bind(&listen_spec).await?;
+
+    println!(
+        "{} continuously recv listening on {}",
+        "*".blue().bold(),
+        listen_spec
+    );
+
+    let counter = AtomicU64::new(1);
+
+    while let Ok((client_stream, client_addr)) = listener.accept().await {
+        let id = counter.fetch_add(1, Ordering::SeqCst);
+        record!([id], "{} new connection from {}", "+".green(), client_addr);
+
+        let target_spec = target_spec.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                handle_encrypt_client_leg(id, client_stream, target_spec, key, inspect).await
+            {
+                error!(
+                    [id],
+                    "error handling connection from {}: {}", client_addr, e
+                );
+            } else {
+                record!([id], "{} connection from {} closed", "-".red(), client_addr);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_encrypt_client_leg(
+    id: u64,
+    client_stream: Stream,
+    target_spec: EndpointSpec,
+    key: [u8; 32],
+    inspect: Inspect,
+) -> Result<()> {
+    let peer_stream = connect_target(&target_spec).await?;
+
+    let client_addr_str = extract_addr(&client_stream);
+    let peer_addr_str = extract_addr(&peer_stream);
+
+    let (mut client_read, mut client_write) = tokio::io::split(client_stream);
+    let (mut peer_read, mut peer_write) = tokio::io::split(peer_stream);
+
+    let encrypt_cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let decrypt_cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+
+    let client_addr_clone = client_addr_str.clone();
+    let peer_addr_clone = peer_addr_str.clone();
+    let encrypt_to_peer = copy_and_record!(
+        encrypt &mut client_read =>
+        &mut peer_write,
+        encrypt_cipher,
+        DIR_CLIENT_TO_SERVER,
+        |plain_len, wire_len, chunk| {
+            let arrow = fmt_addr_forward!(id, (client_addr_clone, >>>, peer_addr_clone));
+            record!([id], "{} - {}", arrow, format!("{} bytes ({} on the wire)", plain_len, wire_len).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    let decrypt_to_client = copy_and_record!(
+        decrypt &mut peer_read =>
+        &mut client_write,
+        decrypt_cipher,
+        DIR_SERVER_TO_CLIENT,
+        |plain_len, wire_len, chunk| {
+            let arrow = fmt_addr_forward!(id, (client_addr_str, <<<, peer_addr_str)).dimmed();
+            record!([id], "{} - {}", arrow, format!("{} bytes ({} on the wire)", plain_len, wire_len).bright_black());
+            if let Some(dump) = render_inspect(id, inspect, chunk) {
+                record!([id], "{}\n{}", arrow, dump);
+            }
+        }
+    );
+
+    tokio::select! {
+        result = encrypt_to_peer => {
+            if let Err(e) = result {
+                error!([id], "error encrypting client to peer transfer: {}", e);
+            }
+        }
+        result = decrypt_to_client => {
+            if let Err(e) = result {
+                error!([id], "error decrypting peer to client transfer: {}", e);
+            }
+        }
+    }
+
+    Ok(())
 }